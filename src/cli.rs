@@ -1,4 +1,17 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+
+/// 统计结果的输出格式
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 人类可读的文本(默认)
+    Text,
+    /// 每个目标一个 JSON 摘要对象
+    Json,
+    /// 每个目标一行 CSV
+    Csv,
+    /// 逐探测 JSON 行 + 最终 JSON 摘要(便于流式消费)
+    Ndjson,
+}
 
 /// TCP Ping - 测试TCP端口连通性的工具
 #[derive(Parser, Debug)]
@@ -11,11 +24,12 @@ use clap::Parser;
   tcping -4 www.example.com                强制使用IPv4
   tcping -6 www.example.com                强制使用IPv6
   tcping -v www.example.com                启用详细输出模式
-  tcping -c www.example.com                启用彩色输出模式")]
+  tcping -c www.example.com                启用彩色输出模式
+  tcping 8.8.8.8/30 example.com -p 443     同时测试整个CIDR地址块和多个主机")]
 pub struct Args {
-    /// 目标主机名或IP地址
+    /// 目标主机名、IP地址或CIDR地址块(可指定多个)
     #[clap(required = true)]
-    pub host: String,
+    pub host: Vec<String>,
 
     /// 目标端口号
     #[clap(short, long, default_value = "80")]
@@ -33,6 +47,19 @@ pub struct Args {
     #[clap(short = 'i', long, default_value = "1000")]
     pub interval: u64,
 
+    /// 高吞吐模式: 每秒发起的连接数(设置后覆盖 --interval，并发探测)
+    ///
+    /// 上限 1_000_000，避免换算出的节拍间隔四舍五入为 0 而使内部定时器崩溃。
+    /// 高吞吐循环只发起普通TCP连接，不会派发 ICMP 或抓包任务，因此与
+    /// `--icmp`/`--icmp-fallback`/`--capture-rtt` 互斥，而不是静默忽略它们。
+    #[clap(
+        short = 'r',
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=1_000_000),
+        conflicts_with_all = ["icmp", "icmp_fallback", "capture_rtt"]
+    )]
+    pub rate: Option<u32>,
+
     /// 强制使用IPv4
     #[clap(short = '4', long, conflicts_with = "ipv6")]
     pub ipv4: bool,
@@ -41,6 +68,26 @@ pub struct Args {
     #[clap(short = '6', long, conflicts_with = "ipv4")]
     pub ipv6: bool,
     
+    /// 使用 ICMP Echo 探测而非 TCP 连接(需要管理员权限)
+    #[clap(long, conflicts_with = "icmp_fallback")]
+    pub icmp: bool,
+
+    /// 当 TCP 连接被拒绝时回退到 ICMP Echo 探测
+    #[clap(long = "icmp-fallback")]
+    pub icmp_fallback: bool,
+
+    /// 通过抓包测量网络RTT，与连接耗时分开统计(需要管理员权限)
+    ///
+    /// 取 SYN 与 SYN-ACK 两段抓包时间戳之差，不解析 TCP 时间戳选项
+    /// (TSval/TSecr)，因此反映的是抓包层面的时间差而非内核级时间戳RTT。
+    /// `--icmp` 模式完全不发 SYN，因此与抓包RTT互斥，而不是静默不产生数据。
+    #[clap(long = "capture-rtt", conflicts_with = "icmp")]
+    pub capture_rtt: bool,
+
+    /// 输出格式 (text|json|csv|ndjson)，便于脚本与监控流水线消费
+    #[clap(short = 'o', long = "output", value_enum, default_value = "text")]
+    pub output: OutputFormat,
+
     /// 启用详细输出模式
     #[clap(short, long)]
     pub verbose: bool,
@@ -1,6 +1,7 @@
 use clap::Parser;
 use colored::*;
-use std::net::SocketAddr;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
@@ -12,8 +13,8 @@ mod network;
 mod stats;
 mod utils;
 
-use cli::Args;
-use network::{resolve_host, tcp_connect};
+use cli::{Args, OutputFormat};
+use network::{expand_cidr, resolve_host, tcp_connect_racing};
 use stats::PingStats;
 use utils::{print_error, setup_signal_handler};
 
@@ -52,46 +53,178 @@ fn print_colored_message(message: &str, color_enabled: bool) {
     }
 }
 
+/// 以 NDJSON 格式打印单次探测结果(供 `--output ndjson` 流式消费)
+fn emit_probe_ndjson(
+    seq_num: u32,
+    target: IpAddr,
+    success: bool,
+    rtt_ms: Option<f64>,
+    error: Option<&str>,
+) {
+    let rtt = match rtt_ms {
+        Some(v) => format!("{v:.3}"),
+        None => "null".to_string(),
+    };
+    let err = match error {
+        Some(e) => format!("\"{}\"", stats::json_escape(e)),
+        None => "null".to_string(),
+    };
+    println!(
+        "{{\"seq\":{seq_num},\"target\":\"{target}\",\"success\":{success},\"rtt_ms\":{rtt},\"error\":{err}}}"
+    );
+}
+
+/// Happy Eyeballs 竞速中相邻尝试之间的错开延迟(毫秒)
+const HAPPY_EYEBALLS_DELAY_MS: u64 = 250;
+
+/// 探测过程中与输出相关的显示参数
+///
+/// 把 `verbose` / `color` / `output` 这几个贯穿各探测函数的开关收拢在一起,
+/// 避免函数签名参数过多。
+#[derive(Clone, Copy)]
+struct ReportOpts {
+    verbose: bool,
+    color: bool,
+    output: OutputFormat,
+}
+
+/// 单次 TCP 探测的结果, 用于区分成功/被拒绝/其它失败
+enum ProbeOutcome {
+    Success,
+    Refused,
+    Failed,
+}
+
+impl ProbeOutcome {
+    fn is_success(&self) -> bool {
+        matches!(self, ProbeOutcome::Success)
+    }
+}
+
 /// 执行单次TCP Ping并返回结果 - 简化超时逻辑
+///
+/// `targets` 为同一目标的全部候选地址, 通过 Happy Eyeballs 竞速连接,
+/// 由最先应答的地址决定本次探测的结果。返回的 `ProbeOutcome` 会把"连接被拒绝"
+/// 与其它失败区分开, 以便 `--icmp-fallback` 仅在被拒绝时回退到 ICMP。
 async fn execute_single_ping(
-    target: &SocketAddr,
+    targets: &[SocketAddr],
     formatted_host: &str, // 预先格式化，避免重复计算
     timeout: u64,
     seq_num: u32,
-    verbose: bool,
-    color_enabled: bool,
-) -> (bool, Option<Duration>) {
+    opts: ReportOpts,
+) -> (ProbeOutcome, Option<Duration>) {
+    let ReportOpts {
+        verbose,
+        color: color_enabled,
+        output,
+    } = opts;
+    let text = output == OutputFormat::Text;
+    let ndjson = output == OutputFormat::Ndjson;
+    let fallback_ip = targets[0].ip();
+
     let start = Instant::now();
-    let result = tcp_connect(target, timeout).await;
+    let result = tcp_connect_racing(targets, timeout, HAPPY_EYEBALLS_DELAY_MS).await;
     let elapsed = start.elapsed();
 
-    if check_timeout(elapsed, timeout, formatted_host, seq_num, verbose) {
-        return (false, None);
+    if elapsed >= Duration::from_millis(timeout) {
+        if text {
+            check_timeout(elapsed, timeout, formatted_host, seq_num, verbose);
+        } else if ndjson {
+            emit_probe_ndjson(seq_num, fallback_ip, false, None, Some("timeout"));
+        }
+        return (ProbeOutcome::Failed, None);
     }
 
     match result {
-        Ok(local_addr) => {
-            let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
-            let success_msg =
-                format!("从 {formatted_host} 收到响应: seq={seq_num} time={elapsed_ms:.2}ms");
-            print_colored_message(&success_msg, color_enabled);
-
-            if verbose {
-                if let Some(addr) = local_addr {
-                    println!("  -> 本地连接详情: {addr} -> {target}");
-                } else {
-                    println!("  -> 无法获取本地连接信息");
+        Ok((winner, local_addr, connect_rtt)) => {
+            // 报告获胜地址自身的建连耗时, 而非整个竞速的墙钟时间(后者含错开等待)
+            let elapsed_ms = connect_rtt.as_secs_f64() * 1000.0;
+            if text {
+                let success_msg =
+                    format!("从 {formatted_host} 收到响应: seq={seq_num} time={elapsed_ms:.2}ms");
+                print_colored_message(&success_msg, color_enabled);
+
+                if verbose {
+                    println!("  -> 应答地址: {winner}");
+                    if let Some(addr) = local_addr {
+                        println!("  -> 本地连接详情: {addr} -> {winner}");
+                    } else {
+                        println!("  -> 无法获取本地连接信息");
+                    }
                 }
+            } else if ndjson {
+                emit_probe_ndjson(seq_num, winner.ip(), true, Some(elapsed_ms), None);
             }
 
-            (true, Some(elapsed))
+            (ProbeOutcome::Success, Some(connect_rtt))
         }
         Err(err) => {
-            let error_msg = format!("从 {formatted_host} 无法连接: seq={seq_num}");
-            print_colored_message(&error_msg, color_enabled);
+            if text {
+                let error_msg = format!("从 {formatted_host} 无法连接: seq={seq_num}");
+                print_colored_message(&error_msg, color_enabled);
 
-            if verbose {
-                println!("  -> 连接失败详情: {err}");
+                if verbose {
+                    println!("  -> 连接失败详情: {err}");
+                }
+            } else if ndjson {
+                emit_probe_ndjson(seq_num, fallback_ip, false, None, Some(&err));
+            }
+
+            // 连接被拒绝说明端口可达但关闭, 与超时/网络不可达区别对待
+            let outcome = if err.contains("refused") || err.contains("拒绝") {
+                ProbeOutcome::Refused
+            } else {
+                ProbeOutcome::Failed
+            };
+            (outcome, None)
+        }
+    }
+}
+
+/// 执行单次 ICMP Echo 探测并返回结果
+///
+/// 应答按 `identifier`/`seq_num` 匹配, RTT 的计算方式与 TCP 探测一致;
+/// 目的不可达 / 超时(TTL 耗尽)在详细模式下作为不同的失败原因打印。
+async fn execute_icmp_ping(
+    client: &surge_ping::Client,
+    ip: std::net::IpAddr,
+    formatted_host: &str,
+    identifier: u16,
+    seq_num: u32,
+    timeout: u64,
+    opts: ReportOpts,
+) -> (bool, Option<Duration>) {
+    let ReportOpts {
+        verbose,
+        color: color_enabled,
+        output,
+    } = opts;
+    let text = output == OutputFormat::Text;
+    let ndjson = output == OutputFormat::Ndjson;
+
+    match network::icmp::echo(client, ip, identifier, seq_num as u16, timeout).await {
+        Ok(rtt) => {
+            let rtt_ms = rtt.as_secs_f64() * 1000.0;
+            if text {
+                let success_msg = format!(
+                    "从 {formatted_host} 收到 ICMP 响应: seq={seq_num} time={rtt_ms:.2}ms"
+                );
+                print_colored_message(&success_msg, color_enabled);
+            } else if ndjson {
+                emit_probe_ndjson(seq_num, ip, true, Some(rtt_ms), None);
+            }
+            (true, Some(rtt))
+        }
+        Err(err) => {
+            if text {
+                let error_msg = format!("从 {formatted_host} 无 ICMP 响应: seq={seq_num}");
+                print_colored_message(&error_msg, color_enabled);
+
+                if verbose {
+                    println!("  -> ICMP 失败详情: {err}");
+                }
+            } else if ndjson {
+                emit_probe_ndjson(seq_num, ip, false, None, Some(&err.to_string()));
             }
 
             (false, None)
@@ -100,52 +233,157 @@ async fn execute_single_ping(
 }
 
 /// 执行TCP Ping循环并收集统计数据 - 优化控制流和字符串处理
-async fn ping_host(ip: std::net::IpAddr, args: &Args, running: Arc<AtomicBool>) -> PingStats {
+///
+/// `label` 为该目标在输出中显示的名称(原始主机名或IP地址)，
+/// 每个目标拥有独立的 `PingStats`，随返回值一并交回给调用方。
+async fn ping_host(
+    label: String,
+    ips: Vec<IpAddr>,
+    args: Arc<Args>,
+    running: Arc<AtomicBool>,
+) -> (String, PingStats, Option<PingStats>) {
     let mut stats = PingStats::new();
-    let target = SocketAddr::new(ip, args.port);
+    let targets: Vec<SocketAddr> = ips
+        .iter()
+        .map(|&ip| SocketAddr::new(ip, args.port))
+        .collect();
 
-    let formatted_host = if ip.is_ipv6() {
-        format!("[{}]:{}", ip, args.port)
+    // 以第一个候选地址作为摘要中显示的主地址
+    let primary = ips[0];
+    let formatted_host = if primary.is_ipv6() {
+        format!("[{}]:{}", primary, args.port)
     } else {
-        format!("{}:{}", args.host, args.port)
+        format!("{}:{}", label, args.port)
     };
 
-    println!(
-        "正在对 {} ({} - {}) 端口 {} 执行 TCP Ping",
-        args.host,
-        if ip.is_ipv4() { "IPv4" } else { "IPv6" },
-        ip,
-        args.port
-    );
+    // 结构化输出模式下不打印人类可读的抬头，保持输出整洁可解析
+    let text = args.output == OutputFormat::Text;
 
-    if args.verbose {
+    if text {
         println!(
-            "测试参数: 超时={} ms, 间隔={} ms, 测试次数={}",
-            args.timeout,
-            args.interval,
-            if args.count == 0 {
-                "无限".to_string()
-            } else {
-                args.count.to_string()
-            }
+            "正在对 {} ({} - {}) 端口 {} 执行 TCP Ping",
+            label,
+            if primary.is_ipv4() { "IPv4" } else { "IPv6" },
+            primary,
+            args.port
         );
+
+        if args.verbose && ips.len() > 1 {
+            println!("候选地址(Happy Eyeballs 竞速): {ips:?}");
+        }
+
+        if args.verbose {
+            println!(
+                "测试参数: 超时={} ms, 间隔={} ms, 测试次数={}",
+                args.timeout,
+                args.interval,
+                if args.count == 0 {
+                    "无限".to_string()
+                } else {
+                    args.count.to_string()
+                }
+            );
+        }
+    }
+
+    // 指定 --rate 时切换到高吞吐并发模式
+    if let Some(rate) = args.rate {
+        let (label, stats) =
+            run_rated(label, targets, formatted_host, args, running, rate, stats).await;
+        return (label, stats, None);
     }
 
+    // --capture-rtt 模式下并行累计抓包测得的网络RTT
+    let mut net_stats = args.capture_rtt.then(PingStats::new);
+    let primary_target = SocketAddr::new(primary, args.port);
+    let icmp_id = std::process::id() as u16;
+    let opts = ReportOpts {
+        verbose: args.verbose,
+        color: args.color,
+        output: args.output,
+    };
+
+    // ICMP 探测复用同一个客户端(raw socket), 避免每次探测都新开套接字
+    let icmp_client = if args.icmp || args.icmp_fallback {
+        match network::icmp::client(primary) {
+            Ok(client) => Some(client),
+            Err(e) => {
+                if text {
+                    println!("  -> 无法创建 ICMP 套接字: {e}");
+                }
+                // ICMP 为主探测方式时套接字不可用则无法继续
+                if args.icmp {
+                    return (label, stats, net_stats);
+                }
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     let mut seq = 0;
     let interval_duration = Duration::from_millis(args.interval);
 
     while running.load(Ordering::Relaxed) && (args.count == 0 || seq < args.count) {
-        let (success, duration) = execute_single_ping(
-            &target,
-            &formatted_host,
-            args.timeout,
-            seq,
-            args.verbose,
-            args.color,
-        )
-        .await;
+        // 在发起连接前开始抓包，与握手并行进行
+        let capture_task = (args.capture_rtt && !args.icmp)
+            .then(|| tokio::spawn(network::capture::measure(primary_target, args.timeout)));
+
+        let (success, duration) = if args.icmp {
+            // args.icmp 为真时 icmp_client 必为 Some(否则已提前返回)
+            let client = icmp_client.as_ref().expect("ICMP 客户端应已就绪");
+            execute_icmp_ping(client, primary, &formatted_host, icmp_id, seq, args.timeout, opts)
+                .await
+        } else {
+            let (outcome, duration) =
+                execute_single_ping(&targets, &formatted_host, args.timeout, seq, opts).await;
+
+            // TCP 被拒绝且开启回退时, 改用 ICMP 再探一次
+            match (&outcome, icmp_client.as_ref()) {
+                (ProbeOutcome::Refused, Some(client)) if args.icmp_fallback => {
+                    if text && args.verbose {
+                        println!("  -> TCP 连接被拒绝，回退到 ICMP 探测");
+                    }
+                    execute_icmp_ping(
+                        client,
+                        primary,
+                        &formatted_host,
+                        icmp_id,
+                        seq,
+                        args.timeout,
+                        opts,
+                    )
+                    .await
+                }
+                _ => (outcome.is_success(), duration),
+            }
+        };
 
         stats.update(success, duration);
+
+        if let (Some(task), Some(net_stats)) = (capture_task, net_stats.as_mut()) {
+            match task.await {
+                Ok(Ok(net)) => {
+                    net_stats.update(true, Some(net.rtt));
+                    if text && args.verbose {
+                        println!("  -> 网络RTT: {:.2}ms", net.rtt.as_secs_f64() * 1000.0);
+                    }
+                }
+                Ok(Err(e)) => {
+                    net_stats.update(false, None);
+                    if text && args.verbose {
+                        println!("  -> 网络RTT测量失败: {e}");
+                    }
+                }
+                Err(e) => {
+                    if text && args.verbose {
+                        println!("  -> 抓包任务异常: {e}");
+                    }
+                }
+            }
+        }
+
         seq += 1;
 
         if !running.load(Ordering::Relaxed) || (args.count > 0 && seq >= args.count) {
@@ -155,31 +393,171 @@ async fn ping_host(ip: std::net::IpAddr, args: &Args, running: Arc<AtomicBool>)
         tokio::time::sleep(interval_duration).await;
     }
 
-    stats
+    (label, stats, net_stats)
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// 高吞吐模式下同时在途的连接数上限
+const MAX_INFLIGHT: usize = 1024;
 
-    let filtered_ips = match resolve_host(&args.host, args.ipv4, args.ipv6, args.verbose) {
-        Ok(ips) => ips,
-        Err(e) => {
-            print_error(&e);
-            return Ok(());
-        }
+/// 固定节拍 + 信号量限流的高吞吐探测循环
+///
+/// 以每秒 `rate` 个的节奏派发连接尝试，每次尝试作为独立的 tokio 任务并发执行，
+/// 结果通过 mpsc 通道汇总进 `PingStats`；信号量限制同时在途的连接数，
+/// 保持与串行模式相同的 count/timeout 语义。
+async fn run_rated(
+    label: String,
+    targets: Vec<SocketAddr>,
+    formatted_host: String,
+    args: Arc<Args>,
+    running: Arc<AtomicBool>,
+    rate: u32,
+    mut stats: PingStats,
+) -> (String, PingStats) {
+    let targets = Arc::new(targets);
+    let formatted_host = Arc::new(formatted_host);
+    let tick = Duration::from_secs_f64(1.0 / rate.max(1) as f64);
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(MAX_INFLIGHT));
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    // 按固定节拍派发探测任务，每个任务先取得一个信号量许可再发起连接
+    let dispatcher = {
+        let running = Arc::clone(&running);
+        let targets = Arc::clone(&targets);
+        let formatted_host = Arc::clone(&formatted_host);
+        let semaphore = Arc::clone(&semaphore);
+        let args = Arc::clone(&args);
+        tokio::spawn(async move {
+            let mut seq = 0u32;
+            let mut ticker = tokio::time::interval(tick);
+            while running.load(Ordering::Relaxed) && (args.count == 0 || seq < args.count) {
+                ticker.tick().await;
+
+                let permit = match Arc::clone(&semaphore).acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => break,
+                };
+
+                let tx = tx.clone();
+                let targets = Arc::clone(&targets);
+                let formatted_host = Arc::clone(&formatted_host);
+                let this_seq = seq;
+                let timeout = args.timeout;
+                let opts = ReportOpts {
+                    verbose: args.verbose,
+                    color: args.color,
+                    output: args.output,
+                };
+
+                tokio::spawn(async move {
+                    let (outcome, duration) =
+                        execute_single_ping(&targets, &formatted_host, timeout, this_seq, opts)
+                            .await;
+                    // 连同发送序号一并回传，便于按序号归属而非到达顺序
+                    let _ = tx.send((this_seq, outcome.is_success(), duration));
+                    drop(permit);
+                });
+
+                seq += 1;
+            }
+        })
     };
 
-    let ip = filtered_ips[0];
+    // 按发送序号顺序折叠进 stats：只缓冲序号大于 next_seq 的乱序结果。
+    // 任务发送结果后即释放许可，所以缓冲量不受 MAX_INFLIGHT 限制，而取决于
+    // 最早一个未完成探测在途期间(至多约一个超时)完成的探测数，大致为 rate × 超时；
+    // 不会像串行收集那样把整次运行(--count 0 时可无限长)都攒在内存里
+    let mut pending: BTreeMap<u32, (bool, Option<Duration>)> = BTreeMap::new();
+    let mut next_seq = 0u32;
+    while let Some((seq, success, duration)) = rx.recv().await {
+        pending.insert(seq, (success, duration));
+        while let Some((success, duration)) = pending.remove(&next_seq) {
+            stats.update(success, duration);
+            next_seq += 1;
+        }
+    }
+    let _ = dispatcher.await;
+
+    // 通道关闭后残留的结果(因任务被中止而永远等不到 next_seq)按序号补记
+    for (_, (success, duration)) in pending {
+        stats.update(success, duration);
+    }
+
+    (label, stats)
+}
+
+/// 将命令行中的每个目标展开为 (显示名称, IP地址) 列表
+///
+/// 主机名保留解析到的全部地址以供 Happy Eyeballs 竞速，CIDR地址块展开为
+/// 其中的每个主机地址(各自作为独立目标)，无法解析/展开的目标会打印错误并跳过。
+fn collect_targets(args: &Args) -> Vec<(String, Vec<IpAddr>)> {
+    let mut targets = Vec::new();
+
+    for host in &args.host {
+        if host.contains('/') {
+            match expand_cidr(host) {
+                Ok(ips) => targets.extend(ips.into_iter().map(|ip| (ip.to_string(), vec![ip]))),
+                Err(e) => print_error(&e),
+            }
+        } else {
+            match resolve_host(host, args.ipv4, args.ipv6, args.verbose) {
+                Ok(ips) => targets.push((host.clone(), ips)),
+                Err(e) => print_error(&e),
+            }
+        }
+    }
+
+    targets
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Arc::new(Args::parse());
+
+    let targets = collect_targets(&args);
+    if targets.is_empty() {
+        return Ok(());
+    }
 
     let running = Arc::new(AtomicBool::new(true));
     setup_signal_handler(running.clone());
 
-    let stats = ping_host(ip, &args, running).await;
+    // 为每个目标启动一个独立的 ping 任务，共享同一个 running 标志
+    let mut handles = Vec::with_capacity(targets.len());
+    for (label, ips) in targets {
+        let args = Arc::clone(&args);
+        let running = Arc::clone(&running);
+        handles.push(tokio::spawn(ping_host(label, ips, args, running)));
+    }
+
+    // CSV 模式下先打印一次表头
+    if args.output == OutputFormat::Csv {
+        println!("{}", PingStats::csv_header());
+    }
 
-    if stats.transmitted > 0 {
-        stats.print_summary(&args.host, args.verbose);
+    // 汇总各目标的统计数据并按所选格式打印逐目标摘要
+    for handle in handles {
+        if let Ok((label, stats, net_stats)) = handle.await {
+            if stats.transmitted > 0 {
+                print_target_summary(&label, &stats, args.output, args.verbose);
+            }
+            // capture-rtt 模式下的网络RTT作为并行统计单独汇总
+            if let Some(net_stats) = net_stats {
+                if net_stats.transmitted > 0 {
+                    let net_label = format!("{label} (网络RTT)");
+                    print_target_summary(&net_label, &net_stats, args.output, args.verbose);
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+/// 按所选输出格式打印单个目标的统计摘要
+fn print_target_summary(label: &str, stats: &PingStats, output: OutputFormat, verbose: bool) {
+    match output {
+        OutputFormat::Text => stats.print_summary(label, verbose),
+        OutputFormat::Json | OutputFormat::Ndjson => println!("{}", stats.to_json(label)),
+        OutputFormat::Csv => println!("{}", stats.to_csv(label)),
+    }
+}
@@ -1,16 +1,162 @@
 use dns_lookup::lookup_host;
-use std::net::{IpAddr, SocketAddr};
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 
-/// 创建TCP连接并返回连接结果和本地地址信息
-pub async fn tcp_connect(addr: &SocketAddr, timeout_ms: u64) -> Result<Option<SocketAddr>, String> {
-    match tokio::time::timeout(Duration::from_millis(timeout_ms), TcpStream::connect(*addr)).await {
-        Ok(Ok(stream)) => match stream.local_addr() {
-            Ok(local_addr) => Ok(Some(local_addr)),
-            Err(_) => Ok(None),
-        },
-        Ok(Err(e)) => Err(e.to_string()),
+/// 单次CIDR展开允许的最大主机数量上限
+///
+/// 没有这个上限, 像 `/0` 或IPv6的 `/64` 这样完全合规的输入会在发出第一个
+/// 探测包之前就尝试同步分配数十亿个地址, 耗尽内存并使整个进程卡死。
+/// 对应IPv4最窄 `/16`(65536个地址)与IPv6最窄 `/112`(65536个地址)。
+const MAX_CIDR_HOSTS: u128 = 1 << 16;
+
+/// 将CIDR地址块(如 `192.168.0.0/30`)展开为其中的主机IP地址列表
+///
+/// 对IPv4会跳过网络地址与广播地址; `/32` 与 `/128` 视为单个地址,
+/// 不做任何跳过或去重。IPv6没有广播地址概念, 会展开整个区间。
+/// 展开后的主机数量超过 [`MAX_CIDR_HOSTS`] 时返回错误, 而不是尝试同步
+/// 分配一个天文数字大小的 `Vec`。
+pub fn expand_cidr(cidr: &str) -> Result<Vec<IpAddr>, String> {
+    let (addr_part, prefix_part) = cidr
+        .split_once('/')
+        .ok_or_else(|| format!("无效的CIDR地址块: {cidr}"))?;
+
+    let prefix: u8 = prefix_part
+        .parse()
+        .map_err(|_| format!("无效的CIDR前缀长度: {cidr}"))?;
+    let base: IpAddr = addr_part
+        .parse()
+        .map_err(|_| format!("无效的CIDR地址: {cidr}"))?;
+
+    match base {
+        IpAddr::V4(v4) => {
+            if prefix > 32 {
+                return Err(format!("无效的CIDR前缀长度: {cidr}"));
+            }
+            let host_bits = 32 - prefix as u32;
+            // /32 表示单个地址
+            if host_bits == 0 {
+                return Ok(vec![IpAddr::V4(v4)]);
+            }
+            if host_bits > 1 && (1u128 << host_bits) - 2 > MAX_CIDR_HOSTS {
+                return Err(format!(
+                    "CIDR地址块过大(超过{MAX_CIDR_HOSTS}个主机): {cidr}"
+                ));
+            }
+
+            let mask = (!0u32).checked_shl(host_bits).unwrap_or(0);
+            let network = u32::from(v4) & mask;
+            let broadcast = network | !mask;
+
+            // /31 点对点链路, 两个地址都可用
+            if host_bits == 1 {
+                return Ok(vec![
+                    IpAddr::V4(Ipv4Addr::from(network)),
+                    IpAddr::V4(Ipv4Addr::from(broadcast)),
+                ]);
+            }
+
+            Ok(((network + 1)..broadcast)
+                .map(|bits| IpAddr::V4(Ipv4Addr::from(bits)))
+                .collect())
+        }
+        IpAddr::V6(v6) => {
+            if prefix > 128 {
+                return Err(format!("无效的CIDR前缀长度: {cidr}"));
+            }
+            let host_bits = 128 - prefix as u32;
+            // /128 表示单个地址
+            if host_bits == 0 {
+                return Ok(vec![IpAddr::V6(v6)]);
+            }
+            if host_bits >= 128 || (1u128 << host_bits) > MAX_CIDR_HOSTS {
+                return Err(format!(
+                    "CIDR地址块过大(超过{MAX_CIDR_HOSTS}个主机): {cidr}"
+                ));
+            }
+
+            let mask = (!0u128).checked_shl(host_bits).unwrap_or(0);
+            let network = u128::from(v6) & mask;
+            let last = network | !mask;
+
+            Ok((network..=last)
+                .map(|bits| IpAddr::V6(Ipv6Addr::from(bits)))
+                .collect())
+        }
+    }
+}
+
+/// 按地址族交替排列候选地址: 先第一个IPv6, 再第一个IPv4, 然后第二个IPv6…
+///
+/// 这是 Happy Eyeballs (RFC 6555) 推荐的尝试顺序, 使双栈主机优先尝试IPv6,
+/// 同时保证在IPv6不可用时能快速回退到IPv4。
+fn interleave_address_families(targets: &[SocketAddr]) -> Vec<SocketAddr> {
+    let mut v6: VecDeque<SocketAddr> = targets.iter().copied().filter(|a| a.is_ipv6()).collect();
+    let mut v4: VecDeque<SocketAddr> = targets.iter().copied().filter(|a| a.is_ipv4()).collect();
+
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !v6.is_empty() || !v4.is_empty() {
+        if let Some(addr) = v6.pop_front() {
+            ordered.push(addr);
+        }
+        if let Some(addr) = v4.pop_front() {
+            ordered.push(addr);
+        }
+    }
+    ordered
+}
+
+/// 以 Happy Eyeballs (RFC 6555) 的方式对多个地址发起竞速连接
+///
+/// 候选地址先按地址族交替排序, 随后逐个以 `delay_ms` 的错开间隔发起
+/// `TcpStream::connect`; 先前的尝试不会被取消, 第一个成功建立的连接获胜,
+/// 其余尚在进行的尝试会在返回时一并中止。返回获胜的远端地址、本地地址,
+/// 以及**该获胜连接自身**的建连耗时(不含错开等待), 使报告的RTT不受竞速错开影响。
+pub async fn tcp_connect_racing(
+    targets: &[SocketAddr],
+    timeout_ms: u64,
+    delay_ms: u64,
+) -> Result<(SocketAddr, Option<SocketAddr>, Duration), String> {
+    if targets.is_empty() {
+        return Err("没有可用的目标地址".into());
+    }
+
+    let ordered = interleave_address_families(targets);
+
+    let mut set = tokio::task::JoinSet::new();
+    for (idx, addr) in ordered.into_iter().enumerate() {
+        let stagger = Duration::from_millis(delay_ms.saturating_mul(idx as u64));
+        set.spawn(async move {
+            if !stagger.is_zero() {
+                tokio::time::sleep(stagger).await;
+            }
+            // 仅对本地址自身的建连计时, 错开等待不计入RTT
+            let start = Instant::now();
+            match TcpStream::connect(addr).await {
+                Ok(stream) => Ok((addr, stream.local_addr().ok(), start.elapsed())),
+                Err(e) => Err(e.to_string()),
+            }
+        });
+    }
+
+    // JoinSet 在函数返回(被 drop)时会中止所有仍在进行的尝试
+    let mut last_err: Option<String> = None;
+    let race = async {
+        while let Some(res) = set.join_next().await {
+            match res {
+                Ok(Ok(win)) => return Ok(win),
+                Ok(Err(e)) => last_err = Some(e),
+                Err(_) => {}
+            }
+        }
+        Err(last_err
+            .take()
+            .unwrap_or_else(|| "所有连接尝试均失败".to_string()))
+    };
+
+    match tokio::time::timeout(Duration::from_millis(timeout_ms), race).await {
+        Ok(result) => result,
         Err(_) => Err("连接超时".into()),
     }
 }
@@ -76,9 +222,343 @@ pub fn resolve_host(
     }
 
     if verbose && filtered_ips.len() > 1 {
-        println!("找到多个IP地址, 使用第一个: {}", filtered_ips[0]);
-        println!("其他备选IP: {:?}", &filtered_ips[1..]);
+        println!("找到多个IP地址, 将通过 Happy Eyeballs 竞速连接: {filtered_ips:?}");
     }
 
     Ok(filtered_ips)
 }
+
+/// 基于原始抓包的网络RTT测量
+///
+/// 在出向接口上抓取本次握手的 SYN 与对应的 SYN-ACK，用源/目的端口配对，
+/// 以两段抓包时间戳之差作为网络RTT，从而与调度/DNS/握手开销分开统计。
+/// 不解析 TCP 时间戳选项(TSval/TSecr)，只是抓包层面的时间差。需要管理员
+/// 权限，通过 `--capture-rtt` 开关启用。
+pub mod capture {
+    use std::net::{IpAddr, SocketAddr};
+    use std::time::Duration;
+
+    /// 一次握手在抓包层面测得的网络RTT
+    pub struct NetworkRtt {
+        /// 测得的网络往返时延(SYN 与 SYN-ACK 两段抓包时间戳之差)
+        pub rtt: Duration,
+    }
+
+    /// 抓到的一个 TCP 段中与配对相关的字段
+    struct Segment {
+        src_port: u16,
+        dst_port: u16,
+        syn: bool,
+        ack: bool,
+        captured_at: Duration,
+    }
+
+    /// 在默认出向接口上抓取与 `target` 的握手并计算网络RTT
+    ///
+    /// 通过 BPF 过滤 `tcp and host <ip> and port <port>`，用发出 SYN 的源端口
+    /// 与返回 SYN-ACK 的目的端口配对，RTT 取两段抓包时间戳之差。
+    pub async fn measure(target: SocketAddr, timeout_ms: u64) -> Result<NetworkRtt, String> {
+        // 抓包属于阻塞式系统调用，放到阻塞线程池中执行
+        tokio::task::spawn_blocking(move || run_capture(target, timeout_ms))
+            .await
+            .map_err(|e| format!("抓包任务失败: {e}"))?
+    }
+
+    /// 在阻塞线程中打开抓包句柄并读取握手段
+    fn run_capture(target: SocketAddr, timeout_ms: u64) -> Result<NetworkRtt, String> {
+        let device = pcap::Device::lookup()
+            .map_err(|e| format!("无法枚举抓包设备: {e}"))?
+            .ok_or_else(|| "未找到可用的抓包设备".to_string())?;
+
+        let mut cap = pcap::Capture::from_device(device)
+            .map_err(|e| format!("无法打开抓包设备: {e}"))?
+            .immediate_mode(true)
+            .timeout(timeout_ms as i32)
+            .open()
+            .map_err(|e| format!("打开抓包会话失败(可能需要管理员权限): {e}"))?;
+
+        // 仅抓取与目标主机该端口相关的 TCP 段
+        let filter = match target.ip() {
+            IpAddr::V4(ip) => format!("tcp and host {ip} and port {}", target.port()),
+            IpAddr::V6(ip) => format!("tcp and host {ip} and port {}", target.port()),
+        };
+        cap.filter(&filter, true)
+            .map_err(|e| format!("设置抓包过滤器失败: {e}"))?;
+
+        let deadline = Duration::from_millis(timeout_ms);
+        let mut outgoing_syn: Option<Segment> = None;
+
+        loop {
+            let packet = match cap.next_packet() {
+                Ok(packet) => packet,
+                Err(pcap::Error::TimeoutExpired) => return Err("抓包超时，未捕获到握手".into()),
+                Err(e) => return Err(format!("读取抓包数据失败: {e}")),
+            };
+
+            let captured_at = Duration::new(
+                packet.header.ts.tv_sec as u64,
+                (packet.header.ts.tv_usec as u32) * 1000,
+            );
+            let Some(seg) = parse_segment(packet.data, captured_at) else {
+                continue;
+            };
+
+            // 发出的 SYN: 目的端口为目标端口, 置 SYN 未置 ACK
+            if seg.syn && !seg.ack && seg.dst_port == target.port() {
+                outgoing_syn = Some(seg);
+                continue;
+            }
+
+            // 返回的 SYN-ACK: 源端口为目标端口, 且与我们 SYN 的源端口配对
+            if seg.syn && seg.ack && seg.src_port == target.port() {
+                if let Some(syn) = outgoing_syn.as_ref() {
+                    if seg.dst_port == syn.src_port {
+                        let rtt = seg.captured_at.saturating_sub(syn.captured_at);
+                        return Ok(NetworkRtt { rtt });
+                    }
+                }
+            }
+
+            if outgoing_syn
+                .as_ref()
+                .is_some_and(|syn| captured_at.saturating_sub(syn.captured_at) > deadline)
+            {
+                return Err("抓包超时，未捕获到 SYN-ACK".into());
+            }
+        }
+    }
+
+    /// 从链路层帧中解析出 TCP 段的关键字段(仅处理以太网 + IPv4/IPv6)
+    fn parse_segment(frame: &[u8], captured_at: Duration) -> Option<Segment> {
+        // 以太网头: 目的/源 MAC 各 6 字节, 之后 2 字节 EtherType
+        let ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+        let (l3, proto_ok, tcp_offset) = match ethertype {
+            0x0800 => {
+                // IPv4: IHL 在首字节低 4 位, 协议号在偏移 9
+                let ihl = (frame.get(14)? & 0x0f) as usize * 4;
+                let proto = *frame.get(14 + 9)?;
+                (14, proto == 6, 14 + ihl)
+            }
+            0x86DD => {
+                // IPv6: 固定 40 字节头, 下一头部在偏移 6
+                let next_header = *frame.get(14 + 6)?;
+                (14, next_header == 6, 14 + 40)
+            }
+            _ => return None,
+        };
+        let _ = l3;
+        if !proto_ok {
+            return None;
+        }
+
+        let src_port = u16::from_be_bytes([*frame.get(tcp_offset)?, *frame.get(tcp_offset + 1)?]);
+        let dst_port =
+            u16::from_be_bytes([*frame.get(tcp_offset + 2)?, *frame.get(tcp_offset + 3)?]);
+        let flags = *frame.get(tcp_offset + 13)?;
+        let syn = flags & 0x02 != 0;
+        let ack = flags & 0x10 != 0;
+
+        Some(Segment {
+            src_port,
+            dst_port,
+            syn,
+            ack,
+            captured_at,
+        })
+    }
+}
+
+/// 基于 ICMP Echo 的可达性探测
+///
+/// 作为 TCP 连接探测之外的另一种手段: 许多主机会丢弃/限速到关闭端口的 TCP，
+/// 却仍会应答 ICMP，反之亦然。应答按 `PingIdentifier`/序列号匹配，RTT 的计算
+/// 方式与 `execute_single_ping` 一致；通过 `--icmp` 或 `--icmp-fallback` 启用。
+pub mod icmp {
+    use std::fmt;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use surge_ping::{Client, Config, PingIdentifier, PingSequence, SurgeError, ICMP};
+
+    /// ICMP 探测的失败原因
+    pub enum IcmpError {
+        /// 在超时阈值内未收到应答
+        Timeout,
+        /// 收到目的不可达(Destination Unreachable)
+        DestinationUnreachable,
+        /// 收到超时(Time Exceeded, 通常为 TTL 耗尽)
+        TimeExceeded,
+        /// 其它错误
+        Other(String),
+    }
+
+    impl fmt::Display for IcmpError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                IcmpError::Timeout => write!(f, "ICMP 超时"),
+                IcmpError::DestinationUnreachable => write!(f, "目的不可达"),
+                IcmpError::TimeExceeded => write!(f, "超时(TTL 耗尽)"),
+                IcmpError::Other(msg) => write!(f, "{msg}"),
+            }
+        }
+    }
+
+    /// 为 `target` 的地址族创建一个持久的 ICMP 客户端(底层 raw socket)
+    ///
+    /// 同一个客户端应在整个探测循环中复用, 避免每次探测都新开一个 raw socket。
+    pub fn client(target: IpAddr) -> Result<Client, IcmpError> {
+        let config = match target {
+            IpAddr::V4(_) => Config::default(),
+            IpAddr::V6(_) => Config::builder().kind(ICMP::V6).build(),
+        };
+        Client::new(&config).map_err(|e| IcmpError::Other(e.to_string()))
+    }
+
+    /// 在已有客户端上发送一个 ICMP Echo Request 并等待与之匹配的应答
+    ///
+    /// `identifier` 与 `seq` 共同标识本次探测，用于把乱序/迟到的应答正确配对;
+    /// `client` 在探测循环中复用, 每次探测不再新开 raw socket。
+    pub async fn echo(
+        client: &Client,
+        target: IpAddr,
+        identifier: u16,
+        seq: u16,
+        timeout_ms: u64,
+    ) -> Result<Duration, IcmpError> {
+        let mut pinger = client.pinger(target, PingIdentifier(identifier)).await;
+        pinger.timeout(Duration::from_millis(timeout_ms));
+
+        // 56 字节载荷, 与常见 ping 实现保持一致
+        let payload = [0u8; 56];
+        match pinger.ping(PingSequence(seq), &payload).await {
+            Ok((_packet, rtt)) => Ok(rtt),
+            Err(e) => Err(classify(e)),
+        }
+    }
+
+    /// 将 `SurgeError` 归类为区分度更高的失败原因
+    fn classify(error: SurgeError) -> IcmpError {
+        if let SurgeError::Timeout { .. } = error {
+            return IcmpError::Timeout;
+        }
+
+        let msg = error.to_string();
+        let lower = msg.to_lowercase();
+        if lower.contains("unreachable") {
+            IcmpError::DestinationUnreachable
+        } else if lower.contains("time exceeded") || lower.contains("ttl") {
+            IcmpError::TimeExceeded
+        } else {
+            IcmpError::Other(msg)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sock(ip: &str, port: u16) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), port)
+    }
+
+    #[test]
+    fn interleave_v4_only_keeps_order() {
+        let targets = [sock("10.0.0.1", 80), sock("10.0.0.2", 80)];
+        assert_eq!(interleave_address_families(&targets), targets);
+    }
+
+    #[test]
+    fn interleave_v6_only_keeps_order() {
+        let targets = [sock("::1", 80), sock("::2", 80)];
+        assert_eq!(interleave_address_families(&targets), targets);
+    }
+
+    #[test]
+    fn interleave_mixed_alternates_starting_with_v6() {
+        let v4_a = sock("10.0.0.1", 80);
+        let v4_b = sock("10.0.0.2", 80);
+        let v6_a = sock("::1", 80);
+        let v6_b = sock("::2", 80);
+
+        // 输入顺序故意把 IPv4 排在前面, 验证交替排序不依赖输入顺序
+        let targets = [v4_a, v4_b, v6_a, v6_b];
+        assert_eq!(
+            interleave_address_families(&targets),
+            vec![v6_a, v4_a, v6_b, v4_b]
+        );
+    }
+
+    #[test]
+    fn interleave_uneven_counts_appends_remainder() {
+        let v4_a = sock("10.0.0.1", 80);
+        let v6_a = sock("::1", 80);
+        let v6_b = sock("::2", 80);
+
+        let targets = [v4_a, v6_a, v6_b];
+        assert_eq!(
+            interleave_address_families(&targets),
+            vec![v6_a, v4_a, v6_b]
+        );
+    }
+
+    #[test]
+    fn expand_cidr_v4_skips_network_and_broadcast() {
+        let ips = expand_cidr("192.168.0.0/30").unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+                IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_cidr_v4_slash31_keeps_both_addresses() {
+        let ips = expand_cidr("192.168.0.0/31").unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)),
+                IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_cidr_v4_slash32_is_single_address() {
+        let ips = expand_cidr("192.168.0.5/32").unwrap();
+        assert_eq!(ips, vec![IpAddr::V4(Ipv4Addr::new(192, 168, 0, 5))]);
+    }
+
+    #[test]
+    fn expand_cidr_v6_slash128_is_single_address() {
+        let ips = expand_cidr("::1/128").unwrap();
+        assert_eq!(ips, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn expand_cidr_rejects_invalid_prefix() {
+        assert!(expand_cidr("192.168.0.0/33").is_err());
+        assert!(expand_cidr("::1/129").is_err());
+        assert!(expand_cidr("not-a-cidr").is_err());
+    }
+
+    #[test]
+    fn expand_cidr_rejects_oversized_v4_range() {
+        // /8 远超 MAX_CIDR_HOSTS, 不应尝试同步展开
+        assert!(expand_cidr("10.0.0.0/8").is_err());
+    }
+
+    #[test]
+    fn expand_cidr_rejects_oversized_v6_range() {
+        // 常规局域网 /64 会展开到 2^64 个地址, 必须在展开前拒绝
+        assert!(expand_cidr("2001:db8::/64").is_err());
+    }
+
+    #[test]
+    fn expand_cidr_accepts_v4_slash16() {
+        let ips = expand_cidr("10.0.0.0/16").unwrap();
+        assert_eq!(ips.len(), (1 << 16) - 2);
+    }
+}
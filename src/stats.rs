@@ -93,6 +93,96 @@ impl PingStats {
         Some((sum_sq_diff / (count - 1) as f64).sqrt())
     }
 
+    // 计算百分位数 - 最近秩(nearest-rank)法
+    //
+    // 对 n 个样本的第 p 百分位: index = ceil(p/100 * n) 并夹到 [1, n],
+    // 取排序后的第 index 个值。
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.rtt_values.is_empty() {
+            return None;
+        }
+
+        let mut sorted_values = self.rtt_values.clone();
+        sorted_values.sort();
+
+        let n = sorted_values.len();
+        let rank = (p / 100.0 * n as f64).ceil() as usize;
+        let index = rank.clamp(1, n);
+        Some(sorted_values[index - 1])
+    }
+
+    // 最大往返时间, 无样本时为 None
+    fn max_time_opt(&self) -> Option<Duration> {
+        (self.received > 0).then_some(self.max_time)
+    }
+
+    // 平均往返时间, 无样本时为 None
+    fn avg_time(&self) -> Option<Duration> {
+        if self.received == 0 {
+            None
+        } else {
+            Some(self.total_time() / self.received)
+        }
+    }
+
+    // 丢包率(百分比)
+    fn loss_percent(&self) -> f64 {
+        if self.transmitted > 0 {
+            (self.transmitted as f64 - self.received as f64) / self.transmitted as f64 * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    // CSV 输出的表头, 与 `to_csv` 的列顺序一致
+    pub fn csv_header() -> &'static str {
+        "target,transmitted,received,loss_percent,min_ms,max_ms,avg_ms,median_ms,stddev_ms,jitter_ms,p90_ms,p95_ms,p99_ms"
+    }
+
+    // 序列化为单行 CSV
+    pub fn to_csv(&self, target: &str) -> String {
+        format!(
+            "{},{},{},{:.1},{},{},{},{},{},{},{},{},{}",
+            csv_field(target),
+            self.transmitted,
+            self.received,
+            self.loss_percent(),
+            csv_ms(self.min_time),
+            csv_ms(self.max_time_opt()),
+            csv_ms(self.avg_time()),
+            csv_ms(self.median_time()),
+            csv_f64(self.std_deviation().map(|s| s * 1000.0)),
+            csv_ms(self.jitter),
+            csv_ms(self.percentile(90.0)),
+            csv_ms(self.percentile(95.0)),
+            csv_ms(self.percentile(99.0)),
+        )
+    }
+
+    // 序列化为单个 JSON 摘要对象
+    pub fn to_json(&self, target: &str) -> String {
+        format!(
+            concat!(
+                "{{\"target\":\"{}\",\"transmitted\":{},\"received\":{},\"loss_percent\":{:.1},",
+                "\"min_ms\":{},\"max_ms\":{},\"avg_ms\":{},\"median_ms\":{},\"stddev_ms\":{},",
+                "\"jitter_ms\":{},\"p90_ms\":{},\"p95_ms\":{},\"p99_ms\":{}}}"
+            ),
+            json_escape(target),
+            self.transmitted,
+            self.received,
+            self.loss_percent(),
+            json_ms(self.min_time),
+            json_ms(self.max_time_opt()),
+            json_ms(self.avg_time()),
+            json_ms(self.median_time()),
+            json_f64(self.std_deviation().map(|s| s * 1000.0)),
+            json_ms(self.jitter),
+            json_ms(self.percentile(90.0)),
+            json_ms(self.percentile(95.0)),
+            json_ms(self.percentile(99.0)),
+        )
+    }
+
     pub fn print_summary(&self, hostname: &str, verbose: bool) {
         println!("\n--- {hostname} TCP ping 统计 ---");
         println!(
@@ -130,7 +220,137 @@ impl PingStats {
                 if let Some(jitter) = self.jitter {
                     println!("抖动(Jitter) = {:.2}ms", jitter.as_secs_f64() * 1000.0);
                 }
+
+                if let (Some(p90), Some(p95), Some(p99)) = (
+                    self.percentile(90.0),
+                    self.percentile(95.0),
+                    self.percentile(99.0),
+                ) {
+                    println!(
+                        "百分位: P90 = {:.2}ms, P95 = {:.2}ms, P99 = {:.2}ms",
+                        p90.as_secs_f64() * 1000.0,
+                        p95.as_secs_f64() * 1000.0,
+                        p99.as_secs_f64() * 1000.0
+                    );
+                }
             }
         }
     }
 }
+
+// 将毫秒数值格式化为 JSON 字段, 无样本时为 `null`
+fn json_ms(value: Option<Duration>) -> String {
+    json_f64(value.map(|d| d.as_secs_f64() * 1000.0))
+}
+
+// 将 f64 格式化为 JSON 字段, `None` 输出为 `null`
+fn json_f64(value: Option<f64>) -> String {
+    match value {
+        Some(v) => format!("{v:.3}"),
+        None => "null".to_string(),
+    }
+}
+
+// 按 RFC 4180 转义 CSV 字段: 含逗号/引号/换行时用双引号包裹, 内部的
+// 引号加倍, 避免 target 标签中的逗号让下游消费者列错位
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// 将毫秒数值格式化为 CSV 字段, 无样本时留空
+fn csv_ms(value: Option<Duration>) -> String {
+    csv_f64(value.map(|d| d.as_secs_f64() * 1000.0))
+}
+
+// 将 f64 格式化为 CSV 字段, `None` 输出为空串
+fn csv_f64(value: Option<f64>) -> String {
+    match value {
+        Some(v) => format!("{v:.3}"),
+        None => String::new(),
+    }
+}
+
+// 转义 JSON 字符串中的特殊字符
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_with(samples_ms: &[u64]) -> PingStats {
+        let mut stats = PingStats::new();
+        for &ms in samples_ms {
+            stats.update(true, Some(Duration::from_millis(ms)));
+        }
+        stats
+    }
+
+    #[test]
+    fn percentile_empty_is_none() {
+        assert_eq!(PingStats::new().percentile(50.0), None);
+    }
+
+    #[test]
+    fn percentile_single_sample_ignores_p() {
+        let stats = stats_with(&[10]);
+        assert_eq!(stats.percentile(0.0), Some(Duration::from_millis(10)));
+        assert_eq!(stats.percentile(100.0), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn percentile_p0_clamps_to_first_rank() {
+        let stats = stats_with(&[10, 20, 30, 40, 50]);
+        assert_eq!(stats.percentile(0.0), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn percentile_p100_is_max() {
+        let stats = stats_with(&[10, 20, 30, 40, 50]);
+        assert_eq!(stats.percentile(100.0), Some(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn percentile_nearest_rank_rounds_up() {
+        // n=10, p90 -> index = ceil(0.9 * 10) = 9 -> sorted[8]
+        let stats = stats_with(&[10, 20, 30, 40, 50, 60, 70, 80, 90, 100]);
+        assert_eq!(stats.percentile(90.0), Some(Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn csv_field_plain_value_is_unquoted() {
+        assert_eq!(csv_field("example.com"), "example.com");
+    }
+
+    #[test]
+    fn csv_field_with_comma_is_quoted() {
+        assert_eq!(csv_field("host,1"), "\"host,1\"");
+    }
+
+    #[test]
+    fn csv_field_with_quote_is_escaped_and_quoted() {
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn to_csv_quotes_comma_containing_target() {
+        let row = stats_with(&[10]).to_csv("host,with,commas");
+        assert!(row.starts_with("\"host,with,commas\","));
+    }
+}